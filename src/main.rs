@@ -1,11 +1,15 @@
 extern crate rand;
 
-use std::sync::{Mutex, Arc};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Condvar, Mutex, Arc};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use rand::distributions::{IndependentSample, Range};
-use std::collections::VecDeque;
-use std::sync::RwLock;
+use rand::{SeedableRng, StdRng};
 
 const STOCK_END_TIME: u64 = 5000;
 const NUM_ITERATIONS: usize = 1000;
@@ -13,66 +17,791 @@ const NUM_PEOPLE: usize = 100;
 const NUM_STOCKS: usize = 20;
 const NUM_STARTING_SHARES_IN_STOCK_EXCHANGE: i32 = 100;
 const NUM_STARTING_SHARES_PERSON: i32 = 50;
+const MIN_LIMIT_PRICE: i32 = 1;
+const MAX_LIMIT_PRICE: i32 = 100;
+// Total shares of any one stock that must exist at all times: the house's
+// starting ask plus what every person starts out holding. `StockChannel`
+// checks this stays constant on every committed trade.
+const TOTAL_SHARES_PER_STOCK: i32 =
+    NUM_STARTING_SHARES_IN_STOCK_EXCHANGE + (NUM_PEOPLE as i32) * NUM_STARTING_SHARES_PERSON;
+// How long a resting order waits to be filled before it cancels itself and
+// the thread abandons it.
+const ORDER_TIMEOUT: Duration = Duration::from_millis(200);
+// A buy order is raced across this many distinct stocks at once; the first
+// one that fills wins and the rest are withdrawn.
+const BUY_RACE_WIDTH: usize = 2;
 
-struct PurchaseRequest {
-    person: thread::Thread,
-    amount: i32,
+// One leg of a buy race: (stock_index, seq, this leg's own order size, shares already
+// credited, remaining counter, waker). The order size shrinks leg to leg by however much
+// earlier legs already filled, so the race can never land more than `amount` total.
+type RaceLeg = (usize, u64, i32, i32, Arc<AtomicI32>, Arc<Condvar>);
+// How long each pass of the race spends waiting on one leg's condition
+// variable before moving on to poll the next leg.
+const RACE_POLL_SLICE: Duration = Duration::from_millis(20);
+
+// Global order sequence number, used to break price ties FIFO-style and to
+// decide whose price "wins" a crossing match (the earlier/resting order).
+static NEXT_ORDER_SEQ: AtomicU64 = AtomicU64::new(0);
+
+fn next_order_seq() -> u64 {
+    NEXT_ORDER_SEQ.fetch_add(1, AtomicOrdering::SeqCst)
+}
+
+/// Picks the RNG seed for this run: the first CLI argument if it parses as a
+/// `u64`, else the `TRUST_EXCHANGE_SEED` environment variable, else `0`.
+/// Each person's `StdRng` is seeded off of it (see `main`), so replaying the
+/// same seed reproduces the same sequence of amounts/stocks/limits each
+/// person draws. Thread scheduling still decides the actual interleaving of
+/// `submit()` calls, so this narrows down a run that trips `StockChannel`'s
+/// conservation check rather than guaranteeing a bit-for-bit replay of it.
+fn resolve_seed() -> u64 {
+    std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .or_else(|| std::env::var("TRUST_EXCHANGE_SEED").ok().and_then(|v| v.parse().ok()))
+        .unwrap_or(0)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Side {
+    Buy,
+    Sell,
+}
+
+/// A resting or in-flight order. `remaining` is shared with the thread that
+/// placed it: the matching engine counts it down in place (under the
+/// stock's mutex) and the owning thread waits until it reads zero, instead
+/// of stashing a `thread::Thread` to unpark.
+///
+/// `waker` is this order's own private `Condvar`, not a stock-wide one: the
+/// matching engine notifies it directly, and only it, the instant this
+/// specific order reaches zero. That makes each order its own designated
+/// waiter — a crossing trade signals exactly the resting orders it actually
+/// filled instead of broadcasting to everyone resting on the book, most of
+/// whom would just wake, find they still have shares left, and go back to
+/// sleep.
+///
+/// Which resting order actually gets matched is a separate question from which one
+/// gets woken: within a price level, `best_fit_index` prefers whichever resting
+/// order the incoming fill can satisfy in full over strict time priority, so a small
+/// order resting behind a much larger one at the same price doesn't sit there
+/// indefinitely while the larger order slowly drains.
+struct Order {
+    seq: u64,
+    side: Side,
+    limit: i32,
+    remaining: Arc<AtomicI32>,
+    waker: Arc<Condvar>,
+}
+
+// BinaryHeap is a max-heap, so bids (want the highest price first) and asks
+// (want the lowest price first) need opposite `Ord` impls. Both break ties
+// by earliest `seq`, so two orders at the same price fill FIFO.
+struct BidOrder(Order);
+struct AskOrder(Order);
+
+impl PartialEq for BidOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.limit == other.0.limit && self.0.seq == other.0.seq
+    }
+}
+impl Eq for BidOrder {}
+impl PartialOrd for BidOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for BidOrder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.limit.cmp(&other.0.limit).then_with(|| other.0.seq.cmp(&self.0.seq))
+    }
+}
+
+impl PartialEq for AskOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.limit == other.0.limit && self.0.seq == other.0.seq
+    }
+}
+impl Eq for AskOrder {}
+impl PartialOrd for AskOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for AskOrder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.limit.cmp(&self.0.limit).then_with(|| other.0.seq.cmp(&self.0.seq))
+    }
+}
+
+/// Picks, among `group` (every order resting at the same price level), whichever one
+/// `incoming_remaining` can satisfy in full — preferring the largest such order, so one
+/// match soaks up as much waiting liquidity as possible, and tie-breaking by earliest
+/// `seq` among equally-sized fits. Falls back to plain earliest-`seq` FIFO if nothing in
+/// `group` fits whole, the same tie-break the book already used before this existed.
+fn best_fit_index(group: &[Order], incoming_remaining: i32) -> usize {
+    let mut best: Option<usize> = None;
+    for (i, o) in group.iter().enumerate() {
+        let candidate_remaining = o.remaining.load(AtomicOrdering::SeqCst);
+        if candidate_remaining > incoming_remaining {
+            continue;
+        }
+        best = Some(match best {
+            None => i,
+            Some(j) => {
+                let best_remaining = group[j].remaining.load(AtomicOrdering::SeqCst);
+                if candidate_remaining > best_remaining
+                    || (candidate_remaining == best_remaining && o.seq < group[j].seq)
+                {
+                    i
+                } else {
+                    j
+                }
+            }
+        });
+    }
+    best.unwrap_or_else(|| group.iter().enumerate().min_by_key(|(_, o)| o.seq).unwrap().0)
 }
 
 struct Stock {
+    bids: BinaryHeap<BidOrder>,
+    asks: BinaryHeap<AskOrder>,
+}
+
+impl Stock {
+    /// The exchange starts out holding `initial_shares` of this stock; model
+    /// that as a single standing ask at the lowest legal price so the
+    /// conservation total still adds up and buyers have something to match
+    /// against before anyone else has sold.
+    fn seeded(initial_shares: i32) -> Stock {
+        let mut stock = Stock {
+            bids: BinaryHeap::new(),
+            asks: BinaryHeap::new(),
+        };
+        stock.asks.push(AskOrder(Order {
+            seq: next_order_seq(),
+            side: Side::Sell,
+            limit: MIN_LIMIT_PRICE,
+            remaining: Arc::new(AtomicI32::new(initial_shares)),
+            waker: Arc::new(Condvar::new()),
+        }));
+        stock
+    }
+
+    /// Matches `order` against the opposite side of the book, executing each
+    /// crossing trade at the *resting* order's price. Any unfilled residual
+    /// is left resting in the book on `order`'s own side. Every `remaining`
+    /// counter touched here (including `order`'s own) is shared with its
+    /// owning thread, which is the only thing this needs to hand back — and
+    /// the moment a resting order's `remaining` reaches zero, its own
+    /// `waker` is notified right here, under the lock, so the one thread
+    /// that's actually done wakes up instead of everyone resting on the book.
+    ///
+    /// `circulating` is the stock's share-conservation counter (see
+    /// `StockChannel`): every `fill` here moves that many shares from
+    /// whichever side held them in the book onto the newly-filled owner, so
+    /// it's credited by `fill` regardless of which side `order` is on.
+    fn match_order(&mut self, order: Order, circulating: &AtomicI32) {
+        loop {
+            let order_remaining = order.remaining.load(AtomicOrdering::SeqCst);
+            if order_remaining <= 0 {
+                break;
+            }
+
+            let resting = match order.side {
+                Side::Buy => self.pop_best_fit_ask(order_remaining, order.limit),
+                Side::Sell => self.pop_best_fit_bid(order_remaining, order.limit),
+            };
+            let resting = match resting {
+                Some(resting) => resting,
+                None => break,
+            };
+
+            let fill = order_remaining.min(resting.remaining.load(AtomicOrdering::SeqCst));
+            order.remaining.fetch_sub(fill, AtomicOrdering::SeqCst);
+            resting.remaining.fetch_sub(fill, AtomicOrdering::SeqCst);
+            circulating.fetch_add(fill, AtomicOrdering::SeqCst);
+
+            if resting.remaining.load(AtomicOrdering::SeqCst) > 0 {
+                match order.side {
+                    Side::Buy => self.asks.push(AskOrder(resting)),
+                    Side::Sell => self.bids.push(BidOrder(resting)),
+                }
+            } else {
+                resting.waker.notify_one();
+            }
+        }
+
+        if order.remaining.load(AtomicOrdering::SeqCst) > 0 {
+            match order.side {
+                Side::Buy => self.bids.push(BidOrder(order)),
+                Side::Sell => self.asks.push(AskOrder(order)),
+            }
+        }
+    }
+
+    /// Pops the ask this incoming buy should match against, or `None` if the best ask
+    /// doesn't cross `limit` at all. Among every resting ask at that best price level,
+    /// prefers whichever one `incoming_remaining` can satisfy in full (largest such
+    /// order wins, tie-broken by earliest `seq`) over strict time priority, so a small
+    /// ask resting behind a much larger one isn't stuck waiting out the whole of the
+    /// larger one before it ever gets a look in. Falls back to plain earliest-`seq` FIFO
+    /// when nothing at that price level fits whole, same as before — that case still
+    /// ends in a partial fill regardless of which order is chosen.
+    ///
+    /// Pulls the whole price level out of the heap into a `Vec` to pick from, which costs
+    /// O(k) in however many orders rest at that price instead of the old O(log n)
+    /// peek-then-pop; `MAX_LIMIT_PRICE` keeps that group small enough in practice for
+    /// this simulation.
+    fn pop_best_fit_ask(&mut self, incoming_remaining: i32, limit: i32) -> Option<Order> {
+        let best_price = self.asks.peek()?.0.limit;
+        if best_price > limit {
+            return None;
+        }
+
+        let mut group = Vec::new();
+        while self.asks.peek().is_some_and(|a| a.0.limit == best_price) {
+            group.push(self.asks.pop().unwrap().0);
+        }
+
+        let chosen = group.remove(best_fit_index(&group, incoming_remaining));
+        for order in group {
+            self.asks.push(AskOrder(order));
+        }
+        Some(chosen)
+    }
+
+    /// Same as `pop_best_fit_ask`, for the bid side of an incoming sell.
+    fn pop_best_fit_bid(&mut self, incoming_remaining: i32, limit: i32) -> Option<Order> {
+        let best_price = self.bids.peek()?.0.limit;
+        if best_price < limit {
+            return None;
+        }
+
+        let mut group = Vec::new();
+        while self.bids.peek().is_some_and(|b| b.0.limit == best_price) {
+            group.push(self.bids.pop().unwrap().0);
+        }
+
+        let chosen = group.remove(best_fit_index(&group, incoming_remaining));
+        for order in group {
+            self.bids.push(BidOrder(order));
+        }
+        Some(chosen)
+    }
+
+    /// Total shares still resting as asks — the book's side of the
+    /// per-stock conservation invariant (bids don't hold any shares; they're
+    /// just unfilled demand).
+    fn ask_total(&self) -> i32 {
+        self.asks.iter().map(|a| a.0.remaining.load(AtomicOrdering::SeqCst)).sum()
+    }
+
+    /// Pulls a resting bid with the given `seq` out of the book, for a buyer
+    /// that timed out or won its race elsewhere. Returns the shares it still
+    /// had resting, if it was there to remove (it may have just been
+    /// matched).
+    fn cancel_bid(&mut self, seq: u64) -> Option<i32> {
+        let mut removed = None;
+        self.bids = self
+            .bids
+            .drain()
+            .filter(|o| {
+                if o.0.seq == seq {
+                    removed = Some(o.0.remaining.load(AtomicOrdering::SeqCst));
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect();
+        removed
+    }
+
+    /// Same as `cancel_bid`, for the ask side.
+    fn cancel_ask(&mut self, seq: u64) -> Option<i32> {
+        let mut removed = None;
+        self.asks = self
+            .asks
+            .drain()
+            .filter(|o| {
+                if o.0.seq == seq {
+                    removed = Some(o.0.remaining.load(AtomicOrdering::SeqCst));
+                    false
+                } else {
+                    true
+                }
+            })
+            .collect();
+        removed
+    }
+
+    /// Empties both sides of the book and returns the total shares that were
+    /// still resting as asks (bids hold no shares, so they add nothing to the
+    /// total). Called once, at shutdown, so the reclaimed total is
+    /// captured in a single pass instead of depending on whichever thread
+    /// happens to wake up and cancel its own order first. Each drained
+    /// order's own `waker` is notified so its thread doesn't stay parked
+    /// waiting for a fill that will now never come.
+    fn drain(&mut self) -> i32 {
+        for o in self.bids.drain() {
+            o.0.waker.notify_one();
+        }
+        let asks: i32 = self
+            .asks
+            .drain()
+            .map(|o| {
+                o.0.waker.notify_one();
+                o.0.remaining.load(AtomicOrdering::SeqCst)
+            })
+            .sum();
+        asks
+    }
+}
+
+/// What woke up a thread resting on a `StockChannel`.
+enum WaitOutcome {
+    /// `remaining` reached zero: the order filled (in full, for a resting
+    /// bid/ask — partial fills don't wake their owner).
+    Filled,
+    /// `ORDER_TIMEOUT` elapsed with shares still unfilled.
+    TimedOut,
+    /// The exchange closed while this order was resting. Its book was
+    /// already drained by `StockChannel::close`, so there's nothing left to
+    /// cancel.
+    Closed,
+}
+
+/// A stock's order book guarded by a mutex. There's deliberately no
+/// stock-wide `Condvar`: each `Order` wakes through its own private `waker`
+/// (see `Order`), so a fill notifies exactly the one thread it completed
+/// instead of every thread resting on the stock. `closed` is flipped at most
+/// once, by `close()`, which drains the book under the same lock so the
+/// reclaimed share count is captured deterministically rather than racing
+/// whichever thread wakes up first to cancel its own order.
+///
+/// `circulating` is this stock's half of a running conservation check: the
+/// total shares of this stock currently held free-and-clear by people,
+/// outside the book. `Stock::ask_total` is the other half — shares currently
+/// resting as asks. The two are only ever supposed to sum to
+/// `TOTAL_SHARES_PER_STOCK`, and every place that moves a share between them
+/// (`submit`, `cancel_ask`) re-checks that under the same lock that made the
+/// move, via `assert_conserved`.
+struct StockChannel {
+    index: usize,
+    stock: Mutex<Stock>,
+    closed: AtomicBool,
+    circulating: AtomicI32,
+}
+
+impl StockChannel {
+    fn new(index: usize, initial_shares: i32) -> StockChannel {
+        StockChannel {
+            index,
+            stock: Mutex::new(Stock::seeded(initial_shares)),
+            closed: AtomicBool::new(false),
+            circulating: AtomicI32::new((NUM_PEOPLE as i32) * NUM_STARTING_SHARES_PERSON),
+        }
+    }
+
+    /// Panics with the offending thread and stock index if `circulating` and
+    /// `stock`'s resting asks no longer add up to `TOTAL_SHARES_PER_STOCK` —
+    /// a share was lost or double-counted in a racy fill. `stock` must be the
+    /// lock guard already held by the caller, so the check sees exactly the
+    /// state the just-committed trade left behind.
+    fn assert_conserved(&self, stock: &Stock) {
+        let book = stock.ask_total();
+        let circulating = self.circulating.load(AtomicOrdering::SeqCst);
+        let total = book + circulating;
+        if total != TOTAL_SHARES_PER_STOCK {
+            panic!(
+                "conservation invariant violated on stock {} by {}: book={} + circulating={} = {}, expected {}",
+                self.index,
+                thread::current().name().unwrap_or("<unknown>"),
+                book,
+                circulating,
+                total,
+                TOTAL_SHARES_PER_STOCK
+            );
+        }
+    }
+
+    /// Matches `order` against the book. Any resting order this fills in
+    /// full is woken via its own `waker` from inside `match_order`. A no-op
+    /// if the exchange has already closed, so nothing new gets queued once
+    /// shutdown has started. A sell commits its shares out of `circulating`
+    /// before matching, since from this point they belong to the book (or
+    /// whoever `match_order` fills) rather than the seller; `assert_conserved`
+    /// then confirms nothing was lost or duplicated in the process.
+    fn submit(&self, order: Order) {
+        let mut stock = self.stock.lock().unwrap();
+        if self.closed.load(AtomicOrdering::SeqCst) {
+            return;
+        }
+        if order.side == Side::Sell {
+            self.circulating.fetch_sub(order.remaining.load(AtomicOrdering::SeqCst), AtomicOrdering::SeqCst);
+        }
+        stock.match_order(order, &self.circulating);
+        self.assert_conserved(&stock);
+    }
+
+    /// Waits for `remaining` to reach zero, `timeout` to elapse, or the
+    /// exchange to close, whichever comes first, parked on `order`'s own
+    /// `waker` rather than a stock-wide `Condvar`. `wait_timeout_while`
+    /// atomically drops the mutex for the duration of the wait and
+    /// reacquires it before returning, so there's no window where the mutex
+    /// is held while parked.
+    fn wait_for_fill(&self, waker: &Condvar, remaining: &AtomicI32, timeout: Duration) -> WaitOutcome {
+        let stock = self.stock.lock().unwrap();
+        let _ = waker
+            .wait_timeout_while(stock, timeout, |_| {
+                remaining.load(AtomicOrdering::SeqCst) > 0
+                    && !self.closed.load(AtomicOrdering::SeqCst)
+            })
+            .unwrap();
+
+        if remaining.load(AtomicOrdering::SeqCst) <= 0 {
+            WaitOutcome::Filled
+        } else if self.closed.load(AtomicOrdering::SeqCst) {
+            WaitOutcome::Closed
+        } else {
+            WaitOutcome::TimedOut
+        }
+    }
+
+    /// Removes a resting bid from the book and hands back the shares it
+    /// still had resting at the moment it was actually removed, or `None` if
+    /// it wasn't there to remove (it may have just been matched). Callers
+    /// that need to know how much this order filled should use this return
+    /// value rather than a `remaining` snapshot taken before the cancel: a
+    /// fill can still land on the order right up until the book's lock
+    /// removes it.
+    fn cancel_bid(&self, seq: u64) -> Option<i32> {
+        self.stock.lock().unwrap().cancel_bid(seq)
+    }
+
+    /// Same as `cancel_bid`, for the ask side. Unlike a bid, a cancelled ask
+    /// hands its leftover shares back to `circulating` — the sale didn't go
+    /// through, so they're the seller's again — and that move gets the same
+    /// `assert_conserved` check as a fill.
+    fn cancel_ask(&self, seq: u64) -> bool {
+        let mut stock = self.stock.lock().unwrap();
+        match stock.cancel_ask(seq) {
+            Some(remaining) => {
+                self.circulating.fetch_add(remaining, AtomicOrdering::SeqCst);
+                self.assert_conserved(&stock);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Shuts the channel: flips `closed` and drains whatever's left in the
+    /// book under the same lock. `Stock::drain` wakes each drained order's
+    /// own `waker` as it goes, so no thread is left asleep. Returns the
+    /// shares reclaimed from the book. Idempotent to call more than once,
+    /// though `main` only ever calls it the one time.
+    fn close(&self) -> i32 {
+        let mut stock = self.stock.lock().unwrap();
+        self.closed.store(true, AtomicOrdering::SeqCst);
+        stock.drain()
+    }
+}
+
+/// One notable thing that happened on a person's trading loop, sent over an
+/// `mpsc` channel to the single render thread instead of each of the 100
+/// person threads calling `println!` directly and racing each other for
+/// stdout. `Placed`/`Queued`/`Filled`/`Abandoned` drive the per-person
+/// progress bar; `Log` carries a free-form line (race outcomes, shutdown
+/// notices) that the render thread prints above the bars rather than
+/// letting it tear one in half.
+enum Event {
+    /// An order was just submitted, with `detail` already formatted for
+    /// display (mirrors the old "attempting to buy/sell" lines).
+    Placed { person: usize, detail: String },
+    /// The order didn't fill immediately and is now resting in the book.
+    Queued { person: usize },
+    /// `shares` filled for `person`, whether immediately or after resting.
+    Filled { person: usize, shares: i32 },
+    /// The order gave up — timed out or the exchange closed — with `shares`
+    /// still unfilled.
+    Abandoned { person: usize, shares: i32 },
+    /// A free-form line to print above the bars.
+    Log(String),
+    /// `person` has finished every iteration; `holdings` is its final
+    /// per-stock share count.
+    Done { person: usize, holdings: Vec<i32> },
+}
+
+// Width, in characters, of the `[====    ]` portion of a person's bar.
+const BAR_WIDTH: usize = 20;
+
+/// Renders a `[====    ]`-style bar showing `done` out of `total`.
+fn render_bar(done: u32, total: u32) -> String {
+    let fraction = if total == 0 { 0 } else { (done as u64 * BAR_WIDTH as u64) / total as u64 };
+    let filled = (fraction as usize).min(BAR_WIDTH);
+    format!("[{}{}]", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled))
+}
+
+/// Per-person state the render thread keeps in order to draw that person's
+/// line of the multi-bar display.
+#[derive(Default)]
+struct PersonProgress {
+    placed: u32,
+    /// Orders placed but not yet filled, abandoned, or cancelled.
+    resting: u32,
+    filled: u32,
+    abandoned: u32,
+    /// Total shares still unfilled across every abandoned order.
+    abandoned_shares: i32,
     shares: i32,
-    queue: VecDeque<PurchaseRequest>,
+    done: bool,
+}
+
+/// Moves the cursor up past the `lines` previously drawn and clears
+/// everything below it, so the next thing written starts from a blank
+/// slate instead of stacking under (or corrupting) the old bars.
+fn clear_bars(lines: usize) {
+    if lines > 0 {
+        print!("\x1B[{}A\x1B[J", lines);
+    }
+}
+
+/// Draws one line per person: a progress bar over orders filled/placed,
+/// followed by how many are still resting and abandoned, and a shares count.
+/// Until the person is done, that count starts at 0 and adds every `Filled`
+/// event's shares, sales as well as purchases, so it is not the shares held;
+/// once done it is replaced by the person's final holdings and a "done"
+/// marker is shown.
+fn draw_bars(people: &[PersonProgress]) {
+    for (person, p) in people.iter().enumerate() {
+        println!(
+            "Person {:3} {} {:4} filled / {:4} placed / {:3} resting / {:3} abandoned ({:4} shares)  shares: {:4}{}",
+            person,
+            render_bar(p.filled, p.placed.max(1)),
+            p.filled,
+            p.placed,
+            p.resting,
+            p.abandoned,
+            p.abandoned_shares,
+            p.shares,
+            if p.done { "  [done]" } else { "" }
+        );
+    }
+}
+
+/// Single consumer of every `Event` in the simulation: drains `rx` until all
+/// `Sender`s are dropped, redrawing the `NUM_PEOPLE`-line progress display
+/// after each update and routing `Log` lines through `clear_bars` first so
+/// they print as their own lines above the redrawn bars instead of
+/// clobbering them. Until it returns, this is the only thread in the whole
+/// program that touches stdout; `main` prints its final total only after
+/// joining it.
+fn run_progress_display(rx: Receiver<Event>) {
+    let mut people: Vec<PersonProgress> = (0..NUM_PEOPLE).map(|_| PersonProgress::default()).collect();
+    let mut lines_drawn = 0;
+
+    for event in rx.iter() {
+        match event {
+            Event::Placed { person, detail } => {
+                people[person].placed += 1;
+                clear_bars(lines_drawn);
+                println!("{}", detail);
+                lines_drawn = 0;
+            }
+            Event::Queued { person } => {
+                people[person].resting += 1;
+            }
+            Event::Filled { person, shares } => {
+                people[person].resting = people[person].resting.saturating_sub(1);
+                people[person].filled += 1;
+                people[person].shares += shares;
+            }
+            Event::Abandoned { person, shares } => {
+                people[person].resting = people[person].resting.saturating_sub(1);
+                people[person].abandoned += 1;
+                people[person].abandoned_shares += shares;
+            }
+            Event::Log(line) => {
+                clear_bars(lines_drawn);
+                println!("{}", line);
+                lines_drawn = 0;
+            }
+            Event::Done { person, holdings } => {
+                people[person].done = true;
+                people[person].shares = holdings.iter().sum();
+            }
+        }
+
+        clear_bars(lines_drawn);
+        draw_bars(&people);
+        lines_drawn = people.len();
+        std::io::stdout().flush().unwrap();
+    }
+}
+
+/// Places an order of `amount` shares for `side` at `limit`, waits for it to
+/// fill, and returns the number of shares actually filled. This is the
+/// guarded wait loop the sell path in `main` uses (the buy path races
+/// several legs with its own polling loop over `wait_for_fill`): it
+/// submits to `channel` immediately, then — if anything is left resting —
+/// waits for it to fill, time out, or the exchange to close, whichever comes
+/// first. Every state change `person` goes through is reported via `output`
+/// instead of a direct `println!`, so the render thread stays the sole
+/// writer of stdout.
+fn trade(
+    channel: &StockChannel,
+    person: usize,
+    output: &Sender<Event>,
+    side: Side,
+    limit: i32,
+    amount: i32,
+) -> i32 {
+    let seq = next_order_seq();
+    let remaining = Arc::new(AtomicI32::new(amount));
+    let waker = Arc::new(Condvar::new());
+    let order = Order { seq, side, limit, remaining: remaining.clone(), waker: waker.clone() };
+
+    channel.submit(order);
+
+    if remaining.load(AtomicOrdering::SeqCst) > 0 {
+        let _ = output.send(Event::Queued { person });
+        match channel.wait_for_fill(&waker, &remaining, ORDER_TIMEOUT) {
+            WaitOutcome::Filled => {}
+            WaitOutcome::TimedOut => {
+                let cancelled = match side {
+                    Side::Buy => channel.cancel_bid(seq).is_some(),
+                    Side::Sell => channel.cancel_ask(seq),
+                };
+                if cancelled {
+                    let _ = output.send(Event::Abandoned {
+                        person,
+                        shares: remaining.load(AtomicOrdering::SeqCst),
+                    });
+                }
+            }
+            WaitOutcome::Closed => {
+                let _ = output.send(Event::Abandoned {
+                    person,
+                    shares: remaining.load(AtomicOrdering::SeqCst),
+                });
+            }
+        }
+    }
+
+    let filled = amount - remaining.load(AtomicOrdering::SeqCst);
+    if filled > 0 {
+        let _ = output.send(Event::Filled { person, shares: filled });
+    }
+    filled
 }
 
 /**
  * Main problem was people waiting on queues that they could never get off of.
- * 1) If everyone is finished except one person that buys, then that person will never get off the
- *    queue assuming insufficient shares available
- *    - To solve this, I initially slept the main thread for some number of seconds before
- *      unparking every thread and joining (to simulate "end of trading day"). This isn't sufficient
- *      because after unparking the thread, they might still loop and get stuck on a queue again.
- *      I guess you can hackishly unpark the thread NUM_ITERATIONS times with enough delay in
- *      between... the workaround I used is to use a RwLock (reader-writer lock) that each person
- *      reads before beginning another iteration. The main thread sets this to true after the
- *      trading day ends so all threads, after being unparked if necessary, will exit the loop
- *      on the next iteration.
- * 2) Everyone can get stuck on a queue. It isn't sufficient to limit size of the queue, because
- *    whatever N you set it to, if everyone except the last N people are finished, all those
- *    N people can end up on the same queue. Workaround: Use the RwLock from above to kill
- *    any stuck processes and ignore the issue completely. I guess another solution is to keep
- *    track of how many people are left/on queues, and adjust the limit dynamically.
- * 3) It is very easy to deadlock by holding the mutex while parking. The scopes have to be
- *    handled very carefully, to ensure the mutex is dropped at the right time.
+ * 1) If everyone is finished except one person that buys, then that person would never get off
+ *    the book assuming insufficient supply ever showed up, and
+ * 2) everyone could get stuck resting at once, with nothing left running to ever match them.
+ *    Both are handled per-order with a hard deadline (`ORDER_TIMEOUT`): on timeout the thread
+ *    re-acquires the stock's mutex, removes its own order from the book (`Stock::cancel_bid`/
+ *    `cancel_ask`), and moves on to its next iteration treating it as abandoned.
+ *
+ *    A buy order also gets to race: the same desired amount is placed as a resting bid on a
+ *    couple of different stocks' books at once, and the moment one of them fills in full, the
+ *    rest are cancelled ("first wakeup wins").
+ * 3) Used to be very easy to deadlock by holding the mutex while parking, since waking a buyer
+ *    meant stashing its raw `thread::Thread` and calling `unpark` by hand. `StockChannel` pairs a
+ *    `Mutex<Stock>` with per-order `Condvar`s: a resting order's thread calls `wait_timeout_while`,
+ *    which atomically drops the mutex for the duration of the wait and reacquires it before
+ *    returning, so there's no window where the mutex is held while parked. `trade` above is the
+ *    guarded wait loop of the sell path, and the buy race waits through the same
+ *    `StockChannel::wait_for_fill`, replacing what used to be near-identical code copy-pasted
+ *    under each.
+ *
+ *    Waking used to go through one stock-wide `Condvar`, so every fill (even a single share
+ *    crossing against one resting order) called `notify_all` and woke everybody resting on that
+ *    stock, most of whom would just find their own `remaining` unchanged and go back to sleep.
+ *    Each `Order` is now its own designated waiter: it carries a private `waker` that
+ *    `Stock::match_order` notifies directly, and only directly, the instant that specific order's
+ *    `remaining` reaches zero. A seller's fill now wakes exactly the buyer(s) it completed.
+ *
+ *    Separately, a crossing trade no longer always matches strict earliest-first within a price
+ *    level: `best_fit_index` prefers whichever resting order at that price the incoming
+ *    fill can satisfy in full, so a small order resting behind a much larger one at the same
+ *    price gets matched as soon as a fill its size comes along instead of waiting out however
+ *    long the larger order takes to drain. Ties (more than one order fits whole, or none do) fall
+ *    back to the original earliest-`seq` order.
  *
- * Instead of using another thread for each stock to check when buyers can be removed off the
- * queue, I took advantage of the fact that the only time something like that can occur
- * is if someone sells. So all sellers (unrealistically) wakes up people off the queue
- * as a way to check when this event occurs.
+ *    End-of-day shutdown used to be a coarser `RwLock<bool>` flag that each thread only noticed
+ *    between iterations, so a thread resting on an order could still be asleep for up to
+ *    `ORDER_TIMEOUT` after the flag flipped. `StockChannel::close` replaces that: it flips a
+ *    per-channel `closed` flag and drains whatever's left in the book in the same critical
+ *    section, notifying each drained order's own `waker` as it goes so nobody is left asleep. Any
+ *    waiter sees `closed` in its wait predicate and returns immediately with `WaitOutcome::Closed`
+ *    instead of looping back to sleep, and since the book was already drained under the close's
+ *    own lock, the reclaimed share count doesn't depend on which thread happens to wake up first
+ *    and cancel its own order — it's fixed the moment `close` returns.
+ *
+ * Orders also carry a limit price: a `Stock` is a real order book (bids sorted highest-price-first,
+ * asks sorted lowest-price-first) instead of a single raw share count, so a buy only crosses asks
+ * priced at or below its limit and a sell only crosses bids priced at or above its limit. Trades
+ * execute at the resting order's price, and a resting order only wakes its owner once it has been
+ * filled in full; partial fills just shrink it in place.
+ *
+ * Every trade also runs past `StockChannel::assert_conserved`, which checks that a stock's
+ * resting asks plus everything people are holding outside the book still add up to
+ * `TOTAL_SHARES_PER_STOCK`, and panics with the offending thread and stock if not. Every person's
+ * RNG is now seeded off of one run-wide seed (`resolve_seed`, overridable via a CLI argument or
+ * `TRUST_EXCHANGE_SEED`) instead of `thread_rng`'s own unpredictable entropy, which at least fixes
+ * the sequence of amounts/stocks/limits each person draws when chasing a conservation panic down
+ * (thread scheduling can still vary the interleaving between people from run to run).
  */
 fn main() {
     let mut stocks = Vec::with_capacity(NUM_STOCKS);
-    for _ in 0..NUM_STOCKS {
-        stocks.push(Arc::new(Mutex::new(Stock {
-            shares: NUM_STARTING_SHARES_IN_STOCK_EXCHANGE,
-            queue: VecDeque::new(),
-        })));
+    for idx in 0..NUM_STOCKS {
+        stocks.push(Arc::new(StockChannel::new(idx, NUM_STARTING_SHARES_IN_STOCK_EXCHANGE)));
     }
 
+    // Every thread in the simulation reports through this channel instead of
+    // calling `println!` itself; `renderer` is the one thread that drains it
+    // and owns stdout.
+    let (output, rx) = mpsc::channel::<Event>();
+    let renderer = thread::Builder::new()
+        .name("renderer".into())
+        .spawn(move || run_progress_display(rx))
+        .unwrap();
+
+    // Every person's RNG is seeded off of this, so a run that trips the
+    // conservation check in `StockChannel::assert_conserved` can be rerun
+    // with the same per-person draws by passing the same seed back in (thread
+    // scheduling can still change the interleaving between people).
+    let seed = resolve_seed();
+    let _ = output.send(Event::Log(format!(
+        "Using RNG seed {} (pass it as the first CLI argument or via TRUST_EXCHANGE_SEED to replay this run)",
+        seed
+    )));
+
     let mut handles = vec![];
-    let should_finish = Arc::new(RwLock::new(false));
+    let exchange_closed = Arc::new(AtomicBool::new(false));
 
     for i in 0..NUM_PEOPLE {
         let mut shares_of_each_stock = vec![NUM_STARTING_SHARES_PERSON as i32; NUM_STOCKS];
         let stocks = stocks.clone();
-        let should_finish = should_finish.clone();
+        let exchange_closed = exchange_closed.clone();
+        let output = output.clone();
         let handle = thread::Builder::new()
             .name(format!("Person {}", i).into())
             .spawn(move || {
-                let mut rng = rand::thread_rng();
+                // Each person's stream is derived from the shared seed plus
+                // its own index, so every thread is deterministic on its own
+                // but no two threads draw the same sequence.
+                let mut rng: StdRng = SeedableRng::from_seed(&[seed.wrapping_add(i as u64) as usize][..]);
 
                 for j in 0..NUM_ITERATIONS {
-                    if *should_finish.read().unwrap() {
-                        println!("Stoppping iteration! Got to iteration {}", j);
+                    if exchange_closed.load(AtomicOrdering::SeqCst) {
+                        let _ = output.send(Event::Log(format!(
+                            "Stoppping iteration! Got to iteration {}",
+                            j
+                        )));
                         break;
                     }
 
@@ -85,130 +814,190 @@ fn main() {
                     let stock_range = Range::new(0, stocks.len());
                     let stock_index = stock_range.ind_sample(&mut rng);
 
+                    // Limit price this order is willing to trade at
+                    let price_range = Range::new(MIN_LIMIT_PRICE, MAX_LIMIT_PRICE + 1);
+                    let limit = price_range.ind_sample(&mut rng);
+
                     if amount < 0 {
                         // Cap the amount they can sell to how much they have
                         if -amount > shares_of_each_stock[stock_index] {
                             amount = -shares_of_each_stock[stock_index];
                         }
+                        let amount = -amount;
+                        if amount == 0 {
+                            continue;
+                        }
 
-                        println!(
-                            "{} attempting to sell {} shares of stock {} on iteration {}",
-                            thread::current().name().unwrap(),
-                            -amount,
-                            stock_index,
-                            j
-                        );
-
-                        let mut stock = stocks[stock_index].lock().unwrap();
-                        stock.shares -= amount;
-                        shares_of_each_stock[stock_index] += amount;
-
-                        // Then check if this enables any buyer to get off the queue
-                        // If so, remove all the buyers from the queue
-                        // that satisfy the change in shares
-                        // This doesn't guarantee they'll get them,
-                        // since another thread could swoop in at that exact moment
-                        // Alternatively, we could modify the woken up buyer's shares here?
-                        let mut estimated_stocks_left = stock.shares;
-                        while stock.queue.len() > 0 && estimated_stocks_left > 0 {
-                            let r = stock.queue.pop_front().unwrap();
-                            // Is there enough shares for this person?
-                            if r.amount <= stock.shares {
-                                estimated_stocks_left -= r.amount;
-                                // Let them try and buy it now
-                                r.person.unpark();
-                            } else {
-                                // Back on the queue it goes
-                                stock.queue.push_front(r);
+                        let _ = output.send(Event::Placed {
+                            person: i,
+                            detail: format!(
+                                "{} attempting to sell {} shares of stock {} at limit {} on iteration {}",
+                                thread::current().name().unwrap(),
+                                amount,
+                                stock_index,
+                                limit,
+                                j
+                            ),
+                        });
+
+                        let sold = trade(&stocks[stock_index], i, &output, Side::Sell, limit, amount);
+                        shares_of_each_stock[stock_index] -= sold;
+                    } else if amount > 0 {
+                        // Race the same desired amount across a few distinct stocks; whichever
+                        // books the full fill first wins and the rest get cancelled.
+                        let mut race_indices = vec![stock_index];
+                        while race_indices.len() < BUY_RACE_WIDTH.min(stocks.len()) {
+                            let idx = stock_range.ind_sample(&mut rng);
+                            if !race_indices.contains(&idx) {
+                                race_indices.push(idx);
+                            }
+                        }
+
+                        let _ = output.send(Event::Placed {
+                            person: i,
+                            detail: format!(
+                                "{} attempting to buy {} shares at limit {} racing stocks {:?} on iteration {}",
+                                thread::current().name().unwrap(),
+                                amount,
+                                limit,
+                                race_indices,
+                                j
+                            ),
+                        });
+
+                        // Each leg only ever asks the book for however much of `amount` is
+                        // still unfilled once the earlier legs have gone in: without that, a
+                        // buy that crosses the seeded standing ask on more than one leg would
+                        // fill in full on every leg it touches instead of `amount` once across
+                        // the whole race.
+                        let mut legs: Vec<RaceLeg> = Vec::new();
+                        let mut total_filled = 0;
+                        for &idx in &race_indices {
+                            let needed = amount - total_filled;
+                            if needed <= 0 {
                                 break;
                             }
+
+                            let seq = next_order_seq();
+                            let remaining = Arc::new(AtomicI32::new(needed));
+                            let waker = Arc::new(Condvar::new());
+                            let order = Order {
+                                seq,
+                                side: Side::Buy,
+                                limit,
+                                remaining: remaining.clone(),
+                                waker: waker.clone(),
+                            };
+
+                            stocks[idx].submit(order);
+
+                            let filled_now = needed - remaining.load(AtomicOrdering::SeqCst);
+                            shares_of_each_stock[idx] += filled_now;
+                            total_filled += filled_now;
+
+                            if remaining.load(AtomicOrdering::SeqCst) > 0 {
+                                legs.push((idx, seq, needed, filled_now, remaining, waker));
+                            }
                         }
-                    } else if amount > 0 {
-                        println!(
-                            "{} attempting to buy {} shares of stock {} on iteration {}",
-                            thread::current().name().unwrap(),
-                            amount,
-                            stock_index,
-                            j
-                        );
-                        // Buying: going on queue
-                        // Runs logic to check if there's something on queue
-                        // If nothing on queue, jump the queue and buy
-                        // If insufficient number of stocks,
-                        // then wait on queue with what's left to buy
-                        // Get on queue
-                        let mut should_park = false;
-                        {
-                            let mut stock = stocks[stock_index].lock().unwrap();
-                            if stock.queue.len() > 0 {
-                                // Someone is ahead of line. Wait
-                                println!(
-                                    "Placing {} on queue (wait time: {}, available: {})",
-                                    thread::current().name().unwrap(),
-                                    stock.queue.len(),
-                                    stock.shares
+
+                        // The whole race is one logical order from the display's point of view,
+                        // and only ever gets one terminal Filled/Abandoned event below — so it
+                        // gets at most one Queued here too, regardless of how many legs ended up
+                        // resting, or `PersonProgress::resting` would count up once per leg but
+                        // only ever count back down once.
+                        if !legs.is_empty() {
+                            let _ = output.send(Event::Queued { person: i });
+                        }
+
+                        // Poll each leg in short slices until one of them fills completely, the
+                        // overall deadline passes, or the exchange closes. A real multi-way wait
+                        // would need a select over several condvars, which std doesn't offer, so
+                        // this is a deliberately simple stand-in for that.
+                        let deadline = Instant::now() + ORDER_TIMEOUT;
+                        let mut winner = None;
+                        let mut closed = false;
+                        'race: while !legs.is_empty() && !closed && Instant::now() < deadline {
+                            for &(idx, _, _, _, ref remaining, ref waker) in &legs {
+                                let slice = RACE_POLL_SLICE.min(
+                                    deadline.saturating_duration_since(Instant::now()),
                                 );
-                                stock.queue.push_back(PurchaseRequest {
-                                    person: thread::current(),
-                                    amount,
-                                });
-                                should_park = true;
-                            } else {
-                                // No line, so buy if possible... otherwise, get on queue
-                                if stock.shares < amount {
-                                    println!(
-                                        "\t{} has to wait ({} available)",
-                                        thread::current().name().unwrap(),
-                                        stock.shares
-                                    );
-
-                                    // Wait until more is available
-                                    stock.queue.push_back(PurchaseRequest {
-                                        person: thread::current(),
-                                        amount,
-                                    });
-                                    should_park = true;
-                                } else {
-                                    stock.shares -= amount;
-                                    shares_of_each_stock[stock_index] += amount;
-                                    println!(
-                                        "{} purchased {} shares of stock {} (current count: {})",
-                                        thread::current().name().unwrap(),
-                                        amount,
-                                        stock_index,
-                                        shares_of_each_stock[stock_index]
-                                    );
+                                match stocks[idx].wait_for_fill(waker, remaining, slice) {
+                                    WaitOutcome::Filled => {
+                                        winner = Some(idx);
+                                        break 'race;
+                                    }
+                                    WaitOutcome::Closed => {
+                                        closed = true;
+                                        break 'race;
+                                    }
+                                    WaitOutcome::TimedOut => {}
                                 }
                             }
                         }
 
-                        if should_park {
-                            thread::park();
-                            println!("\t{} is now awake!", thread::current().name().unwrap());
-
-                            let mut stock = stocks[stock_index].lock().unwrap();
-
-                            // Repeated code from above...
-                            // Did not want to re-obtain the mutex
-                            if stock.shares >= amount {
-                                stock.shares -= amount;
-                                shares_of_each_stock[stock_index] += amount;
-                                println!(
-                                    "{} purchased {} shares of stock {} (current count: {})",
-                                    thread::current().name().unwrap(),
-                                    amount,
-                                    stock_index,
-                                    shares_of_each_stock[stock_index]
-                                );
+                        // Each leg's `remaining` only ever shrinks as its own book matches it, so
+                        // the total filled over its lifetime is `leg_amount - remaining`;
+                        // `credited` is the slice of that we already added above, so only the
+                        // delta is new. A losing leg is cancelled *before* its fill is read,
+                        // since a fill can still land on it right up until `cancel_bid`'s lock
+                        // actually removes it from the book — `cancel_bid`'s own return is the
+                        // remaining it saw at that exact moment, so crediting from that instead
+                        // of a pre-cancel snapshot can't silently drop a fill that lands in the
+                        // gap between deciding to cancel and the cancel actually happening.
+                        //
+                        // That gap can still hand a losing (or un-won, timed-out) leg a genuine
+                        // fill from some unrelated seller between the race being decided and its
+                        // own `cancel_bid` call actually reaching the book, which would otherwise
+                        // let the race land more than `amount` total. `total_credited` is every
+                        // share this race has credited so far, across every leg; nothing is ever
+                        // allowed to push it past `amount`.
+                        let mut total_credited: i32 = legs.iter().map(|&(_, _, _, credited, _, _)| credited).sum();
+                        for &(idx, seq, leg_amount, credited, ref remaining, _) in &legs {
+                            let filled_total = if Some(idx) != winner && !closed {
+                                match stocks[idx].cancel_bid(seq) {
+                                    Some(left) => leg_amount - left,
+                                    None => leg_amount - remaining.load(AtomicOrdering::SeqCst),
+                                }
                             } else {
-                                // Okay, too bad, they waited and didn't get anything, they'll
-                                // just need to deal with it and try to buy/sell something else!
-                                println!("Giving up!");
+                                leg_amount - remaining.load(AtomicOrdering::SeqCst)
+                            };
+                            if filled_total > credited {
+                                let delta = (filled_total - credited).min((amount - total_credited).max(0));
+                                shares_of_each_stock[idx] += delta;
+                                total_credited += delta;
                             }
                         }
+
+                        if let Some(idx) = winner {
+                            let _ = output.send(Event::Filled { person: i, shares: amount });
+                            let _ = output.send(Event::Log(format!(
+                                "\t{} won the race on stock {} (current count: {})",
+                                thread::current().name().unwrap(),
+                                idx,
+                                shares_of_each_stock[idx]
+                            )));
+                        } else if closed {
+                            let _ = output.send(Event::Abandoned { person: i, shares: amount - total_credited });
+                            let _ = output.send(Event::Log(format!(
+                                "\t{} exchange closed mid-race, giving up",
+                                thread::current().name().unwrap()
+                            )));
+                        } else if !legs.is_empty() {
+                            let _ = output.send(Event::Abandoned { person: i, shares: amount - total_credited });
+                            let _ = output.send(Event::Log(format!(
+                                "\t{} timed out waiting on the race, giving up",
+                                thread::current().name().unwrap()
+                            )));
+                        } else {
+                            // No leg was ever left resting: every share of `amount` was already
+                            // matched synchronously against earlier legs' submits (e.g. the
+                            // seeded standing ask), so there was nothing left to race.
+                            let _ = output.send(Event::Filled { person: i, shares: amount });
+                        }
                     }
                 }
 
+                let _ = output.send(Event::Done { person: i, holdings: shares_of_each_stock.clone() });
                 shares_of_each_stock
             })
             .unwrap();
@@ -218,25 +1007,31 @@ fn main() {
 
     thread::sleep(Duration::from_millis(STOCK_END_TIME));
 
-    // Signal that all threads should finish
-    let mut should_finish = should_finish.write().unwrap();
-    *should_finish = true;
+    // Close every channel: each `close()` drains its own book under its own lock and wakes
+    // anyone resting on it, so no thread is left asleep and the reclaimed total below is fixed
+    // the moment this loop finishes, independent of how the joins below happen to interleave.
+    exchange_closed.store(true, AtomicOrdering::SeqCst);
+    let mut reclaimed = 0;
+    for channel in stocks.iter() {
+        reclaimed += channel.close();
+    }
 
-    let mut sum = 0;
+    let mut sum = reclaimed;
     for handle in handles {
-        handle.thread().unpark();
         let vals = handle.join().unwrap();
-        println!("Stock count for: {:?}", vals);
+        let _ = output.send(Event::Log(format!("Stock count for: {:?}", vals)));
         for val in vals {
             sum += val;
         }
     }
 
-    for stock in stocks.iter() {
-        let shares = stock.lock().unwrap().shares;
-        println!("Stock value: {}", shares);
-        sum += shares;
-    }
+    let _ = output.send(Event::Log(format!("Shares reclaimed at close: {}", reclaimed)));
+
+    // Dropping the last `Sender` closes the channel, which ends `rx.iter()` in
+    // the renderer and lets the final bars settle before anything further is
+    // printed.
+    drop(output);
+    renderer.join().unwrap();
 
     println!(
         "Program finished. Total sum = {} (expected {})",
@@ -245,3 +1040,130 @@ fn main() {
             (NUM_PEOPLE * NUM_STOCKS * (NUM_STARTING_SHARES_PERSON as usize))
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: Side, limit: i32, amount: i32) -> Order {
+        Order {
+            seq: next_order_seq(),
+            side,
+            limit,
+            remaining: Arc::new(AtomicI32::new(amount)),
+            waker: Arc::new(Condvar::new()),
+        }
+    }
+
+    fn empty_book() -> Stock {
+        Stock { bids: BinaryHeap::new(), asks: BinaryHeap::new() }
+    }
+
+    #[test]
+    fn non_crossing_buy_rests_in_book() {
+        let mut book = empty_book();
+        book.asks.push(AskOrder(order(Side::Sell, 50, 10)));
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(order(Side::Buy, 40, 5), &circulating);
+
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(book.ask_total(), 10);
+        assert_eq!(circulating.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[test]
+    fn crossing_buy_fills_in_full_against_a_cheaper_ask() {
+        let mut book = empty_book();
+        book.asks.push(AskOrder(order(Side::Sell, 40, 10)));
+
+        let buy_remaining = Arc::new(AtomicI32::new(5));
+        let buy = Order {
+            seq: next_order_seq(),
+            side: Side::Buy,
+            limit: 50,
+            remaining: buy_remaining.clone(),
+            waker: Arc::new(Condvar::new()),
+        };
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(buy, &circulating);
+
+        assert_eq!(buy_remaining.load(AtomicOrdering::SeqCst), 0);
+        assert_eq!(book.ask_total(), 5);
+        assert_eq!(circulating.load(AtomicOrdering::SeqCst), 5);
+    }
+
+    #[test]
+    fn oversized_buy_partially_fills_and_rests_the_remainder() {
+        let mut book = empty_book();
+        book.asks.push(AskOrder(order(Side::Sell, 40, 4)));
+
+        let buy_remaining = Arc::new(AtomicI32::new(10));
+        let buy = Order {
+            seq: next_order_seq(),
+            side: Side::Buy,
+            limit: 50,
+            remaining: buy_remaining.clone(),
+            waker: Arc::new(Condvar::new()),
+        };
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(buy, &circulating);
+
+        assert_eq!(buy_remaining.load(AtomicOrdering::SeqCst), 6);
+        assert_eq!(book.ask_total(), 0);
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(circulating.load(AtomicOrdering::SeqCst), 4);
+    }
+
+    #[test]
+    fn asks_at_the_same_price_fill_fifo_by_seq() {
+        let mut book = empty_book();
+        let first = order(Side::Sell, 40, 3);
+        let first_seq = first.seq;
+        book.asks.push(AskOrder(first));
+        book.asks.push(AskOrder(order(Side::Sell, 40, 3)));
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(order(Side::Buy, 40, 3), &circulating);
+
+        // The earlier-seq ask should have been the one consumed, leaving only
+        // the later one resting.
+        assert_eq!(book.ask_total(), 3);
+        assert!(book.asks.iter().all(|a| a.0.seq != first_seq));
+    }
+
+    #[test]
+    fn sell_that_does_not_cross_rests_as_an_ask() {
+        let mut book = empty_book();
+        book.bids.push(BidOrder(order(Side::Buy, 30, 10)));
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(order(Side::Sell, 40, 5), &circulating);
+
+        assert_eq!(book.ask_total(), 5);
+        assert_eq!(book.bids.len(), 1);
+        assert_eq!(circulating.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[test]
+    fn small_ask_fills_whole_ahead_of_an_earlier_larger_ask_at_the_same_price() {
+        let mut book = empty_book();
+        let large = order(Side::Sell, 40, 5);
+        let large_seq = large.seq;
+        book.asks.push(AskOrder(large));
+        let small = order(Side::Sell, 40, 2);
+        let small_seq = small.seq;
+        book.asks.push(AskOrder(small));
+
+        let circulating = AtomicI32::new(0);
+        book.match_order(order(Side::Buy, 40, 2), &circulating);
+
+        // The smaller ask fits the incoming fill exactly, so it's the one consumed even
+        // though the larger ask was placed first — the larger one is left resting untouched.
+        assert!(book.asks.iter().all(|a| a.0.seq != small_seq));
+        assert!(book.asks.iter().any(|a| a.0.seq == large_seq && a.0.remaining.load(AtomicOrdering::SeqCst) == 5));
+        assert_eq!(circulating.load(AtomicOrdering::SeqCst), 2);
+    }
+}